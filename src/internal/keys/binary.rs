@@ -4,38 +4,88 @@
 // can obtain one at http://mozilla.org/MPL/2.0/.
 
 use cbor::{Decoder, Encoder};
+use cbor::types::Type;
 use internal::util::{Bytes64, Bytes32, DecodeError, DecodeResult, EncodeResult};
 use sodiumoxide::crypto::scalarmult as ecdh;
 use sodiumoxide::crypto::sign;
 use std::io::{Read, Write};
 use super::*;
 
+// A tagged key is encoded as its own 2-element CBOR array `[tag, bytes]`,
+// nested inside the parent array as a single item, so that adding the tag
+// never changes the declared length of whatever frames the key (see
+// `enc_identity_keypair`, `enc_prekey`, `enc_prekey_bundle`).
+//
+// A bare bytestring (the pre-tag V1 wire format) is still accepted on
+// decode and treated as Ed25519, so keys serialized before this change
+// keep decoding.
+fn peek_type<R: Read>(d: &mut Decoder<R>) -> DecodeResult<Type> {
+    let (ty, _) = try!(d.kernel().typeinfo());
+    Ok(ty)
+}
+
 // SecretKey ////////////////////////////////////////////////////////////////
 
 pub fn enc_secret_key<W: Write>(k: &SecretKey, e: &mut Encoder<W>) -> EncodeResult<()> {
+    try!(e.array(2));
+    try!(e.u8(k.algorithm.to_tag()));
     e.bytes(&k.sec_edward.0).map_err(From::from)
 }
 
 pub fn dec_secret_key<R: Read>(d: &mut Decoder<R>) -> DecodeResult<SecretKey> {
-    Bytes64::decode(d).map(|v| {
-        let ed = sign::SecretKey(v.array);
-        let ck = ecdh::Scalar(from_ed25519_sk(&ed));
-        SecretKey { sec_edward: ed, sec_curve: ck }
-    })
+    let algorithm = match try!(peek_type(d)) {
+        Type::Array => {
+            let n = try!(d.array());
+            if n != 2 {
+                return Err(DecodeError::InvalidArrayLen(n))
+            }
+            let tag = try!(d.u8());
+            try!(Algorithm::from_tag(tag).ok_or(DecodeError::UnsupportedAlgorithm(tag)))
+        }
+        _ => Algorithm::Ed25519 // legacy, untagged V1 format
+    };
+    match algorithm {
+        Algorithm::Ed25519 => {
+            Bytes64::decode(d).map(|v| {
+                let ed = sign::SecretKey(v.array);
+                let ck = ecdh::Scalar(from_ed25519_sk(&ed));
+                SecretKey { algorithm: Algorithm::Ed25519, sec_edward: ed, sec_curve: ck }
+            })
+        }
+        a => Err(DecodeError::UnsupportedAlgorithm(a.to_tag()))
+    }
 }
 
 // PublicKey ////////////////////////////////////////////////////////////////
 
 pub fn enc_public_key<W: Write>(k: &PublicKey, e: &mut Encoder<W>) -> EncodeResult<()> {
+    try!(e.array(2));
+    try!(e.u8(k.algorithm.to_tag()));
     e.bytes(&k.pub_edward.0).map_err(From::from)
 }
 
 pub fn dec_public_key<R: Read>(d: &mut Decoder<R>) -> DecodeResult<PublicKey> {
-    Bytes32::decode(d).map(|v| {
-        let ed = sign::PublicKey(v.array);
-        let ck = ecdh::GroupElement(from_ed25519_pk(&ed));
-        PublicKey { pub_edward: ed, pub_curve: ck }
-    })
+    let algorithm = match try!(peek_type(d)) {
+        Type::Array => {
+            let n = try!(d.array());
+            if n != 2 {
+                return Err(DecodeError::InvalidArrayLen(n))
+            }
+            let tag = try!(d.u8());
+            try!(Algorithm::from_tag(tag).ok_or(DecodeError::UnsupportedAlgorithm(tag)))
+        }
+        _ => Algorithm::Ed25519 // legacy, untagged V1 format
+    };
+    match algorithm {
+        Algorithm::Ed25519 => {
+            Bytes32::decode(d).map(|v| {
+                let ed = sign::PublicKey(v.array);
+                let ck = ecdh::GroupElement(from_ed25519_pk(&ed));
+                PublicKey { algorithm: Algorithm::Ed25519, pub_edward: ed, pub_curve: ck }
+            })
+        }
+        a => Err(DecodeError::UnsupportedAlgorithm(a.to_tag()))
+    }
 }
 
 // Identity Key /////////////////////////////////////////////////////////////
@@ -63,6 +113,29 @@ pub fn dec_version<R: Read>(d: &mut Decoder<R>) -> DecodeResult<Version> {
     }
 }
 
+// BundleVersion ////////////////////////////////////////////////////////////
+
+pub fn enc_bundle_version<W: Write>(v: &BundleVersion, e: &mut Encoder<W>) -> EncodeResult<()> {
+    match *v {
+        BundleVersion::V1        => e.u16(1).map_err(From::from),
+        BundleVersion::V2 { .. } => e.u16(2).map_err(From::from)
+    }
+}
+
+// The `V2` signature isn't known until the rest of the bundle has been
+// decoded (see `dec_prekey_bundle`), so decoding only ever needs to know
+// which arm to take, not a fully-formed `BundleVersion` — hence this
+// private wire-only tag instead of reusing `BundleVersion` itself.
+enum BundleVersionTag { V1, V2 }
+
+fn dec_bundle_version<R: Read>(d: &mut Decoder<R>) -> DecodeResult<BundleVersionTag> {
+    match try!(d.u16()) {
+        1 => Ok(BundleVersionTag::V1),
+        2 => Ok(BundleVersionTag::V2),
+        v => Err(DecodeError::InvalidVersion(format!("unknown prekey bundle version {}", v)))
+    }
+}
+
 // Identity Keypair /////////////////////////////////////////////////////////
 
 pub fn enc_identity_keypair<W: Write>(k: &IdentityKeyPair, e: &mut Encoder<W>) -> EncodeResult<()> {
@@ -131,30 +204,69 @@ pub fn dec_prekey<R: Read>(d: &mut Decoder<R>) -> DecodeResult<PreKey> {
 
 // Prekey Bundle ////////////////////////////////////////////////////////////
 
+// The bytes that a V2 bundle's signature is computed over: the
+// CBOR-serialized ephemeral public key. Kept in one place so the signing
+// side (`PreKeyBundle::signed`) and the verifying side (`dec_prekey_bundle`)
+// can never drift apart.
+pub fn prekey_public_key_bytes(k: &PublicKey) -> EncodeResult<Vec<u8>> {
+    let mut buf = Vec::new();
+    try!(enc_public_key(k, &mut Encoder::new(&mut buf)));
+    Ok(buf)
+}
+
+pub fn sign_public_key(k: &PublicKey, sk: &sign::SecretKey) -> sign::Signature {
+    let bytes = prekey_public_key_bytes(k).expect("encoding a public key cannot fail");
+    sign::sign_detached(&bytes, sk)
+}
+
 pub fn enc_prekey_bundle<W: Write>(k: &PreKeyBundle, e: &mut Encoder<W>) -> EncodeResult<()> {
     match k.version {
-        Version::V1 => {
+        BundleVersion::V1 => {
             try!(e.array(4));
-            try!(enc_version(k.version, e));
+            try!(enc_bundle_version(&k.version, e));
             try!(enc_prekey_id(&k.prekey_id, e));
             try!(enc_public_key(&k.public_key, e));
             enc_identity_key(&k.identity_key, e)
         }
+        BundleVersion::V2 { ref signature } => {
+            try!(e.array(5));
+            try!(enc_bundle_version(&k.version, e));
+            try!(enc_prekey_id(&k.prekey_id, e));
+            try!(enc_public_key(&k.public_key, e));
+            try!(enc_identity_key(&k.identity_key, e));
+            e.bytes(&signature.0).map_err(From::from)
+        }
     }
 }
 
 pub fn dec_prekey_bundle<R: Read>(d: &mut Decoder<R>) -> DecodeResult<PreKeyBundle> {
     let n = try!(d.array());
-    let v = try!(dec_version(d));
+    let v = try!(dec_bundle_version(d));
     match v {
-        Version::V1 => {
+        BundleVersionTag::V1 => {
             if n != 4 {
                 return Err(DecodeError::InvalidArrayLen(n))
             }
             let id = try!(dec_prekey_id(d));
             let pk = try!(dec_public_key(d));
             let ik = try!(dec_identity_key(d));
-            Ok(PreKeyBundle { version: v, prekey_id: id, public_key: pk, identity_key: ik })
+            Ok(PreKeyBundle { version: BundleVersion::V1, prekey_id: id, public_key: pk, identity_key: ik })
+        }
+        BundleVersionTag::V2 => {
+            if n != 5 {
+                return Err(DecodeError::InvalidArrayLen(n))
+            }
+            let id  = try!(dec_prekey_id(d));
+            let pk  = try!(dec_public_key(d));
+            let ik  = try!(dec_identity_key(d));
+            let sig = sign::Signature(try!(Bytes64::decode(d)).array);
+
+            let bytes = try!(prekey_public_key_bytes(&pk).map_err(DecodeError::from));
+            if !sign::verify_detached(&sig, &bytes, &ik.public_key.pub_edward) {
+                return Err(DecodeError::InvalidSignature)
+            }
+
+            Ok(PreKeyBundle { version: BundleVersion::V2 { signature: sig }, prekey_id: id, public_key: pk, identity_key: ik })
         }
     }
 }
@@ -176,8 +288,10 @@ pub fn dec_keypair<R: Read>(d: &mut Decoder<R>) -> DecodeResult<KeyPair> {
 
 #[cfg(test)]
 mod tests {
-    use internal::keys::KeyPair;
+    use cbor::Config;
+    use internal::keys::{IdentityKeyPair, KeyPair, PreKey, PreKeyId, PreKeyBundle};
     use internal::util::roundtrip;
+    use std::io::Cursor;
     use super::*;
 
     #[test]
@@ -196,4 +310,50 @@ mod tests {
         assert_eq!(&k.secret_key.sec_edward.0[..], &r.sec_edward.0[..]);
         assert_eq!(&k.secret_key.sec_curve.0[..], &r.sec_curve.0[..])
     }
+
+    #[test]
+    fn enc_dec_prekey_bundle_v2() {
+        let ident  = IdentityKeyPair::new();
+        let prekey = PreKey::new(PreKeyId(1));
+        let bundle = PreKeyBundle::signed(&ident, &prekey);
+        let r = roundtrip(|mut e| enc_prekey_bundle(&bundle, &mut e),
+                          |mut d| dec_prekey_bundle(&mut d));
+        assert_eq!(bundle.public_key, r.public_key);
+        assert_eq!(bundle.identity_key, r.identity_key)
+    }
+
+    #[test]
+    fn prekey_bundle_v2_rejects_tampered_public_key() {
+        let ident   = IdentityKeyPair::new();
+        let prekey  = PreKey::new(PreKeyId(1));
+        let swapped = PreKey::new(PreKeyId(2));
+        let mut bundle = PreKeyBundle::signed(&ident, &prekey);
+        bundle.public_key = swapped.key_pair.public_key;
+
+        let mut buf = Cursor::new(Vec::new());
+        enc_prekey_bundle(&bundle, &mut Encoder::new(&mut buf)).unwrap();
+        buf.set_position(0);
+        let mut d = Decoder::new(Config::default(), buf);
+        match dec_prekey_bundle(&mut d) {
+            Err(DecodeError::InvalidSignature) => (),
+            _ => panic!("expected InvalidSignature")
+        }
+    }
+
+    #[test]
+    fn dec_pubkey_rejects_unsupported_algorithm() {
+        let mut buf = Cursor::new(Vec::new());
+        {
+            let mut e = Encoder::new(&mut buf);
+            e.array(2).unwrap();
+            e.u8(1).unwrap(); // Algorithm::Nistp256, not implemented yet
+            e.bytes(&[0u8; 32]).unwrap();
+        }
+        buf.set_position(0);
+        let mut d = Decoder::new(Config::default(), buf);
+        match dec_public_key(&mut d) {
+            Err(DecodeError::UnsupportedAlgorithm(1)) => (),
+            _ => panic!("expected UnsupportedAlgorithm(1)")
+        }
+    }
 }
@@ -0,0 +1,91 @@
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License, v. 2.0. If a copy of
+// the MPL was not distributed with this file, You
+// can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Human-comparable fingerprints ("safety numbers") for `IdentityKey`s, so
+//! two parties can verify out-of-band that they are talking to the identity
+//! they think they are (i.e. detect a MITM on the key exchange).
+
+use sodiumoxide::crypto::hash::sha256;
+use super::IdentityKey;
+
+/// Bumped whenever the derivation below changes, so old and new fingerprints
+/// are never silently compared against each other.
+const FINGERPRINT_VERSION: u32 = 1;
+
+/// Number of times the digest is re-hashed, chosen to add a small but
+/// noticeable brute-force cost without making verification annoying.
+const FINGERPRINT_ITERATIONS: u32 = 5000;
+
+/// A human-readable, six-group decimal fingerprint of a single identity key.
+pub fn fingerprint(ik: &IdentityKey) -> String {
+    render(&digest(ik))
+}
+
+/// A combined fingerprint both parties can compute independently and
+/// compare, e.g. by reading it aloud or scanning a QR code of it. The two
+/// per-identity fingerprints are concatenated in a canonical (sorted) order
+/// so that either side arrives at the same string regardless of who is
+/// "local" and who is "remote".
+pub fn safety_number(local: &IdentityKey, remote: &IdentityKey) -> String {
+    let mut parts = [fingerprint(local), fingerprint(remote)];
+    parts.sort();
+    parts.concat()
+}
+
+fn digest(ik: &IdentityKey) -> [u8; 32] {
+    let mut input = Vec::new();
+    input.push(ik.public_key.algorithm.to_tag());
+    input.extend_from_slice(&ik.public_key.pub_edward.0);
+    input.extend_from_slice(&[
+        (FINGERPRINT_VERSION >> 24) as u8,
+        (FINGERPRINT_VERSION >> 16) as u8,
+        (FINGERPRINT_VERSION >> 8)  as u8,
+        FINGERPRINT_VERSION         as u8
+    ]);
+
+    let mut h = sha256::hash(&input).0;
+    for _ in 0 .. FINGERPRINT_ITERATIONS {
+        h = sha256::hash(&h).0;
+    }
+    h
+}
+
+// Render the leading 30 bytes (six 40-bit chunks) of a digest as six
+// zero-padded five-digit decimal groups, separated by spaces.
+fn render(digest: &[u8; 32]) -> String {
+    let mut groups = Vec::with_capacity(6);
+    for chunk in digest[..30].chunks(5) {
+        let n = chunk.iter().fold(0u64, |acc, &b| (acc << 8) | b as u64);
+        groups.push(format!("{:05}", n % 100000));
+    }
+    groups.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use internal::keys::IdentityKeyPair;
+    use super::*;
+
+    #[test]
+    fn fingerprint_is_deterministic() {
+        let ident = IdentityKeyPair::new();
+        assert_eq!(fingerprint(&ident.public_key), fingerprint(&ident.public_key))
+    }
+
+    #[test]
+    fn fingerprint_differs_between_identities() {
+        let a = IdentityKeyPair::new();
+        let b = IdentityKeyPair::new();
+        assert!(fingerprint(&a.public_key) != fingerprint(&b.public_key))
+    }
+
+    #[test]
+    fn safety_number_is_order_independent() {
+        let a = IdentityKeyPair::new();
+        let b = IdentityKeyPair::new();
+        assert_eq!(safety_number(&a.public_key, &b.public_key),
+                   safety_number(&b.public_key, &a.public_key))
+    }
+}
@@ -0,0 +1,293 @@
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License, v. 2.0. If a copy of
+// the MPL was not distributed with this file, You
+// can obtain one at http://mozilla.org/MPL/2.0/.
+
+use internal::util::zero;
+use sodiumoxide::crypto::pwhash;
+use sodiumoxide::crypto::scalarmult as ecdh;
+use sodiumoxide::crypto::sign;
+use sodiumoxide::crypto::hash::sha256;
+
+pub mod binary;
+pub mod fingerprint;
+pub mod pem;
+
+// Version //////////////////////////////////////////////////////////////////
+
+// Framing version for `IdentityKeyPair` and `PreKey`. These have never had
+// more than one wire representation, so this stays a single-variant enum
+// rather than sharing `BundleVersion` below — that keeps every `match` on
+// it exhaustive without a catch-all arm that could silently swallow a
+// future variant.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Version { V1 }
+
+// `PreKeyBundle` alone grew a second wire format (see `PreKeyBundle::signed`),
+// so it gets its own version type instead of reusing `Version`. The `V2`
+// signature is carried directly on the variant rather than as a separate
+// `Option` field on `PreKeyBundle`, so "V2 without a signature" can't be
+// constructed at all.
+#[derive(Clone)]
+pub enum BundleVersion {
+    V1,
+    V2 { signature: sign::Signature }
+}
+
+// Algorithm ////////////////////////////////////////////////////////////////
+
+/// The key agreement primitive a `PublicKey`/`SecretKey` was generated for.
+/// Tagged on the wire so new curves can be added without breaking the
+/// decoding of keys generated by older versions of this crate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Algorithm {
+    /// Ed25519 for signing, converted to Curve25519 for X25519 agreement.
+    /// This is the only algorithm implemented today.
+    Ed25519,
+    /// Reserved for NIST P-256 agreement keys.
+    Nistp256,
+    /// Reserved for NIST P-384 agreement keys.
+    Nistp384
+}
+
+impl Algorithm {
+    pub fn to_tag(self) -> u8 {
+        match self {
+            Algorithm::Ed25519  => 0,
+            Algorithm::Nistp256 => 1,
+            Algorithm::Nistp384 => 2
+        }
+    }
+
+    pub fn from_tag(tag: u8) -> Option<Algorithm> {
+        match tag {
+            0 => Some(Algorithm::Ed25519),
+            1 => Some(Algorithm::Nistp256),
+            2 => Some(Algorithm::Nistp384),
+            _ => None
+        }
+    }
+}
+
+// KeyPair //////////////////////////////////////////////////////////////////
+
+#[derive(Clone)]
+pub struct KeyPair {
+    pub secret_key: SecretKey,
+    pub public_key: PublicKey
+}
+
+impl KeyPair {
+    pub fn new() -> KeyPair {
+        let (pk, sk) = sign::gen_keypair();
+        KeyPair::from_sign_keypair(pk, sk)
+    }
+
+    /// Deterministically derive a key pair from a 32-byte seed, e.g. so an
+    /// identity can be regenerated from a memorized secret instead of
+    /// relying on persisted storage.
+    pub fn from_seed(seed: &[u8; 32]) -> KeyPair {
+        let (pk, sk) = sign::keypair_from_seed(&sign::Seed(*seed));
+        KeyPair::from_sign_keypair(pk, sk)
+    }
+
+    fn from_sign_keypair(pk: sign::PublicKey, sk: sign::SecretKey) -> KeyPair {
+        let cpk = ecdh::GroupElement(from_ed25519_pk(&pk));
+        let csk = ecdh::Scalar(from_ed25519_sk(&sk));
+        KeyPair {
+            secret_key: SecretKey { algorithm: Algorithm::Ed25519, sec_edward: sk, sec_curve: csk },
+            public_key: PublicKey { algorithm: Algorithm::Ed25519, pub_edward: pk, pub_curve: cpk }
+        }
+    }
+}
+
+// SecretKey ////////////////////////////////////////////////////////////////
+
+#[derive(Clone)]
+pub struct SecretKey {
+    pub algorithm:  Algorithm,
+    pub sec_edward: sign::SecretKey,
+    pub sec_curve:  ecdh::Scalar
+}
+
+impl Drop for SecretKey {
+    fn drop(&mut self) {
+        zero(&mut self.sec_edward.0);
+        zero(&mut self.sec_curve.0);
+    }
+}
+
+// PublicKey ////////////////////////////////////////////////////////////////
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct PublicKey {
+    pub algorithm:  Algorithm,
+    pub pub_edward: sign::PublicKey,
+    pub pub_curve:  ecdh::GroupElement
+}
+
+// IdentityKey //////////////////////////////////////////////////////////////
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct IdentityKey {
+    pub public_key: PublicKey
+}
+
+// IdentityKeyPair //////////////////////////////////////////////////////////
+
+#[derive(Clone)]
+pub struct IdentityKeyPair {
+    pub version:    Version,
+    pub secret_key: SecretKey,
+    pub public_key: IdentityKey
+}
+
+impl IdentityKeyPair {
+    pub fn new() -> IdentityKeyPair {
+        IdentityKeyPair::from_key_pair(KeyPair::new())
+    }
+
+    /// Derive an identity deterministically from a passphrase: the
+    /// passphrase is run through argon2id (deliberately slow, unlike a bare
+    /// hash, to raise the cost of an offline brute-force guess against a
+    /// memorized secret) to produce a 32-byte seed, which is then used as
+    /// the Ed25519 signing seed — the "brain wallet" idea from `ethkey`,
+    /// hardened with a real KDF work factor. The same passphrase always
+    /// yields the same identity, and the result round-trips through
+    /// `enc_identity_keypair` like any other keypair.
+    pub fn derive(passphrase: &str) -> IdentityKeyPair {
+        let seed = derive_seed(passphrase);
+        IdentityKeyPair::from_key_pair(KeyPair::from_seed(&seed))
+    }
+
+    fn from_key_pair(k: KeyPair) -> IdentityKeyPair {
+        IdentityKeyPair {
+            version:    Version::V1,
+            secret_key: k.secret_key,
+            public_key: IdentityKey { public_key: k.public_key }
+        }
+    }
+}
+
+// PreKeyId /////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PreKeyId(pub u16);
+
+// PreKey ///////////////////////////////////////////////////////////////////
+
+pub struct PreKey {
+    pub version:  Version,
+    pub key_id:   PreKeyId,
+    pub key_pair: KeyPair
+}
+
+impl PreKey {
+    pub fn new(id: PreKeyId) -> PreKey {
+        PreKey { version: Version::V1, key_id: id, key_pair: KeyPair::new() }
+    }
+}
+
+// PreKeyBundle /////////////////////////////////////////////////////////////
+
+pub struct PreKeyBundle {
+    pub version:      BundleVersion,
+    pub prekey_id:    PreKeyId,
+    pub public_key:   PublicKey,
+    pub identity_key: IdentityKey
+}
+
+impl PreKeyBundle {
+    pub fn new(ident: IdentityKey, key: &PreKey) -> PreKeyBundle {
+        PreKeyBundle {
+            version:      BundleVersion::V1,
+            prekey_id:    key.key_id,
+            public_key:   key.key_pair.public_key.clone(),
+            identity_key: ident
+        }
+    }
+
+    // Sign the ephemeral public key with the identity's long-term Ed25519
+    // key so a recipient can verify that the bundle was not tampered with
+    // in transit.
+    pub fn signed(ident: &IdentityKeyPair, key: &PreKey) -> PreKeyBundle {
+        let pk  = key.key_pair.public_key.clone();
+        let sig = binary::sign_public_key(&pk, &ident.secret_key.sec_edward);
+        PreKeyBundle {
+            version:      BundleVersion::V2 { signature: sig },
+            prekey_id:    key.key_id,
+            public_key:   pk,
+            identity_key: ident.public_key.clone()
+        }
+    }
+}
+
+// Passphrase -> seed (KDF) ///////////////////////////////////////////////
+
+// Fixed, non-secret domain-separation salt: `IdentityKeyPair::derive` has
+// no persisted-per-user salt to draw on (the whole point is that the same
+// passphrase always regenerates the same identity from memory alone), so
+// this only needs to keep proteus brain-wallets from colliding with other
+// applications' argon2 usage of the same passphrase, not to be unique
+// per key.
+const BRAINKEY_SALT: [u8; pwhash::SALTBYTES] = *b"proteus-brainkey";
+
+fn derive_seed(passphrase: &str) -> [u8; 32] {
+    let mut seed = [0u8; 32];
+    let salt = pwhash::Salt(BRAINKEY_SALT);
+    pwhash::derive_key(&mut seed,
+                        passphrase.as_bytes(),
+                        &salt,
+                        pwhash::OPSLIMIT_INTERACTIVE,
+                        pwhash::MEMLIMIT_INTERACTIVE)
+        .expect("argon2id seed derivation failed");
+    seed
+}
+
+// Ed25519 -> Curve25519 conversion ////////////////////////////////////////
+
+pub fn from_ed25519_sk(k: &sign::SecretKey) -> [u8; 32] {
+    let mut buf = [0u8; 32];
+    let digest = sha256::hash(&k.0[..32]);
+    buf.copy_from_slice(&digest.0[..32]);
+    buf[0]  &= 248;
+    buf[31] &= 127;
+    buf[31] |= 64;
+    buf
+}
+
+pub fn from_ed25519_pk(k: &sign::PublicKey) -> [u8; 32] {
+    // Montgomery-form conversion of the Edwards point `k`.
+    let mut buf = [0u8; 32];
+    buf.copy_from_slice(&k.0);
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use internal::keys::binary::{enc_identity_keypair, dec_identity_keypair};
+    use internal::util::roundtrip;
+    use super::*;
+
+    #[test]
+    fn derive_is_deterministic() {
+        let a = IdentityKeyPair::derive("correct horse battery staple");
+        let b = IdentityKeyPair::derive("correct horse battery staple");
+        assert_eq!(a.public_key, b.public_key)
+    }
+
+    #[test]
+    fn derive_differs_between_passphrases() {
+        let a = IdentityKeyPair::derive("correct horse battery staple");
+        let b = IdentityKeyPair::derive("Tr0ub4dor&3");
+        assert!(a.public_key != b.public_key)
+    }
+
+    #[test]
+    fn derived_identity_roundtrips() {
+        let ident = IdentityKeyPair::derive("correct horse battery staple");
+        let r = roundtrip(|mut e| enc_identity_keypair(&ident, &mut e),
+                          |mut d| dec_identity_keypair(&mut d));
+        assert_eq!(ident.public_key, r.public_key)
+    }
+}
@@ -0,0 +1,109 @@
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License, v. 2.0. If a copy of
+// the MPL was not distributed with this file, You
+// can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Textual (PEM-like) armoring for the CBOR-encoded key types in this
+//! module. This does not invent a new binary format: it just wraps the
+//! existing `enc_*`/`dec_*` codecs from `binary` in a base64 envelope that
+//! is convenient to paste into config files or diff in version control.
+
+use cbor::{Decoder, Encoder};
+use internal::keys::binary;
+use internal::util::{DecodeError, DecodeResult, EncodeResult};
+use rustc_serialize::base64::{FromBase64, ToBase64, STANDARD};
+use super::{IdentityKey, IdentityKeyPair, PreKey, PreKeyBundle};
+
+const LABEL_IDENTITY_KEYPAIR: &'static str = "PROTEUS IDENTITY KEYPAIR";
+const LABEL_IDENTITY_KEY:     &'static str = "PROTEUS IDENTITY KEY";
+const LABEL_PREKEY:           &'static str = "PROTEUS PREKEY";
+const LABEL_PREKEY_BUNDLE:    &'static str = "PROTEUS PREKEY BUNDLE";
+
+/// The result of parsing a PEM block whose label was not known up front.
+pub enum PemObject {
+    IdentityKeyPair(IdentityKeyPair),
+    IdentityKey(IdentityKey),
+    PreKey(PreKey),
+    PreKeyBundle(PreKeyBundle)
+}
+
+fn armor(label: &str, body: &[u8]) -> String {
+    format!("-----BEGIN {l}-----\n{b}\n-----END {l}-----\n", l = label, b = body.to_base64(STANDARD))
+}
+
+fn dearmor(pem: &str) -> DecodeResult<(String, Vec<u8>)> {
+    let begin = try!(pem.find("-----BEGIN ").ok_or_else(|| DecodeError::InvalidPemLabel(String::new())));
+    let head_end = try!(pem[begin..].find("-----\n").map(|i| begin + i + 5)
+        .ok_or_else(|| DecodeError::InvalidPemLabel(String::new())));
+    let label = pem[begin + 11 .. head_end - 5].to_string();
+    let footer = format!("-----END {}-----", label);
+    let body_end = try!(pem[head_end..].find(&footer)
+        .ok_or_else(|| DecodeError::InvalidPemLabel(label.clone())));
+    let body = pem[head_end .. head_end + body_end].trim();
+    let bytes = try!(body.from_base64().map_err(|_| DecodeError::InvalidPemLabel(label.clone())));
+    Ok((label, bytes))
+}
+
+fn to_pem<F>(label: &str, enc: F) -> EncodeResult<String>
+where F: FnOnce(&mut Encoder<&mut Vec<u8>>) -> EncodeResult<()> {
+    let mut buf = Vec::new();
+    try!(enc(&mut Encoder::new(&mut buf)));
+    Ok(armor(label, &buf))
+}
+
+pub fn to_pem_identity_keypair(k: &IdentityKeyPair) -> EncodeResult<String> {
+    to_pem(LABEL_IDENTITY_KEYPAIR, |e| binary::enc_identity_keypair(k, e))
+}
+
+pub fn to_pem_identity_key(k: &IdentityKey) -> EncodeResult<String> {
+    to_pem(LABEL_IDENTITY_KEY, |e| binary::enc_identity_key(k, e))
+}
+
+pub fn to_pem_prekey(k: &PreKey) -> EncodeResult<String> {
+    to_pem(LABEL_PREKEY, |e| binary::enc_prekey(k, e))
+}
+
+pub fn to_pem_prekey_bundle(k: &PreKeyBundle) -> EncodeResult<String> {
+    to_pem(LABEL_PREKEY_BUNDLE, |e| binary::enc_prekey_bundle(k, e))
+}
+
+/// Strip the PEM armor, base64-decode the body and dispatch to the `dec_*`
+/// function matching the block's label.
+pub fn from_pem(pem: &str) -> DecodeResult<PemObject> {
+    let (label, bytes) = try!(dearmor(pem));
+    let mut d = Decoder::new(::cbor::Config::default(), ::std::io::Cursor::new(bytes));
+    match &label[..] {
+        LABEL_IDENTITY_KEYPAIR => binary::dec_identity_keypair(&mut d).map(PemObject::IdentityKeyPair),
+        LABEL_IDENTITY_KEY     => binary::dec_identity_key(&mut d).map(PemObject::IdentityKey),
+        LABEL_PREKEY           => binary::dec_prekey(&mut d).map(PemObject::PreKey),
+        LABEL_PREKEY_BUNDLE    => binary::dec_prekey_bundle(&mut d).map(PemObject::PreKeyBundle),
+        _                      => Err(DecodeError::InvalidPemLabel(label))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pem_roundtrip_identity_keypair() {
+        let ident = IdentityKeyPair::new();
+        let pem   = to_pem_identity_keypair(&ident).unwrap();
+        assert!(pem.starts_with("-----BEGIN PROTEUS IDENTITY KEYPAIR-----"));
+        match from_pem(&pem).unwrap() {
+            PemObject::IdentityKeyPair(r) => assert_eq!(ident.public_key, r.public_key),
+            _                             => panic!("expected an IdentityKeyPair")
+        }
+    }
+
+    #[test]
+    fn pem_from_pem_rejects_label_mismatch() {
+        let ident = IdentityKeyPair::new();
+        let pem   = to_pem_identity_key(&ident.public_key).unwrap();
+        let bad   = pem.replace("PROTEUS IDENTITY KEY", "PROTEUS BOGUS");
+        match from_pem(&bad) {
+            Err(DecodeError::InvalidPemLabel(_)) => (),
+            _                                     => panic!("expected InvalidPemLabel")
+        }
+    }
+}
@@ -0,0 +1,142 @@
+// This Source Code Form is subject to the terms of
+// the Mozilla Public License, v. 2.0. If a copy of
+// the MPL was not distributed with this file, You
+// can obtain one at http://mozilla.org/MPL/2.0/.
+
+use cbor::Decoder;
+use cbor::CborError;
+#[cfg(test)]
+use cbor::Encoder;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::io::Cursor;
+use std::ptr;
+use std::sync::atomic::{self, Ordering};
+
+pub type EncodeResult<A> = Result<A, CborError>;
+pub type DecodeResult<A> = Result<A, DecodeError>;
+
+// DecodeError //////////////////////////////////////////////////////////////
+
+#[derive(Debug)]
+pub enum DecodeError {
+    Wrapped(CborError),
+    InvalidArrayLen(usize),
+    InvalidVersion(String),
+    InvalidSignature,
+    InvalidPemLabel(String),
+    UnsupportedAlgorithm(u8)
+}
+
+impl From<CborError> for DecodeError {
+    fn from(e: CborError) -> DecodeError {
+        DecodeError::Wrapped(e)
+    }
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            DecodeError::Wrapped(ref e)         => write!(f, "DecodeError: {}", e),
+            DecodeError::InvalidArrayLen(n)     => write!(f, "DecodeError: invalid array length: {}", n),
+            DecodeError::InvalidVersion(ref msg) => write!(f, "DecodeError: {}", msg),
+            DecodeError::InvalidSignature        => write!(f, "DecodeError: invalid bundle signature"),
+            DecodeError::InvalidPemLabel(ref l)  => write!(f, "DecodeError: unexpected PEM label: {}", l),
+            DecodeError::UnsupportedAlgorithm(t) => write!(f, "DecodeError: unsupported key algorithm tag: {}", t)
+        }
+    }
+}
+
+impl Error for DecodeError {
+    fn description(&self) -> &str {
+        "DecodeError"
+    }
+}
+
+// Zeroing //////////////////////////////////////////////////////////////////
+
+/// Overwrite `bytes` with zeroes in a way the compiler is not allowed to
+/// optimize away, so secret key material does not linger in freed memory.
+pub fn zero(bytes: &mut [u8]) {
+    for b in bytes.iter_mut() {
+        unsafe { ptr::write_volatile(b, 0) }
+    }
+    atomic::compiler_fence(Ordering::SeqCst);
+}
+
+// Bytes32 //////////////////////////////////////////////////////////////////
+
+pub struct Bytes32 {
+    pub array: [u8; 32]
+}
+
+impl Bytes32 {
+    pub fn new(array: [u8; 32]) -> Bytes32 {
+        Bytes32 { array: array }
+    }
+
+    pub fn decode<R: ::std::io::Read>(d: &mut Decoder<R>) -> DecodeResult<Bytes32> {
+        let v = try!(d.bytes());
+        if v.len() != 32 {
+            return Err(DecodeError::InvalidArrayLen(v.len()))
+        }
+        let mut a = [0u8; 32];
+        a.copy_from_slice(&v);
+        Ok(Bytes32::new(a))
+    }
+}
+
+// Bytes64 //////////////////////////////////////////////////////////////////
+
+pub struct Bytes64 {
+    pub array: [u8; 64]
+}
+
+impl Bytes64 {
+    pub fn new(array: [u8; 64]) -> Bytes64 {
+        Bytes64 { array: array }
+    }
+
+    pub fn decode<R: ::std::io::Read>(d: &mut Decoder<R>) -> DecodeResult<Bytes64> {
+        let mut v = try!(d.bytes());
+        if v.len() != 64 {
+            zero(&mut v);
+            return Err(DecodeError::InvalidArrayLen(v.len()))
+        }
+        let mut a = [0u8; 64];
+        a.copy_from_slice(&v);
+        zero(&mut v);
+        Ok(Bytes64::new(a))
+    }
+}
+
+impl Drop for Bytes64 {
+    fn drop(&mut self) {
+        zero(&mut self.array)
+    }
+}
+
+// Test helper //////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+pub fn roundtrip<A, E, D>(enc: E, dec: D) -> A
+where E: FnOnce(Encoder<&mut Cursor<Vec<u8>>>) -> EncodeResult<()>,
+      D: FnOnce(Decoder<Cursor<Vec<u8>>>) -> DecodeResult<A>
+{
+    let mut buf = Cursor::new(Vec::new());
+    enc(Encoder::new(&mut buf)).unwrap();
+    buf.set_position(0);
+    dec(Decoder::new(cbor::Config::default(), buf)).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_overwrites_all_bytes() {
+        let mut bytes = [0xAAu8; 64];
+        zero(&mut bytes);
+        assert_eq!(&bytes[..], &[0u8; 64][..])
+    }
+}